@@ -4,7 +4,7 @@ Use [`Config`] to setup the REPL and use [`Repl`] to interact with it.
 ```rust
 # tokio_test::block_on(async {
 # use rusty_nodejs_repl::{Repl, Config, Error};
-let mut repl: Repl = Config::build()?.start()?;
+let repl: Repl = Config::build()?.start()?;
 let result = repl.run("console.log('Hello, world!');").await?;
 assert_eq!(result, b"Hello, world!\n");
 repl.stop().await?;
@@ -14,21 +14,39 @@ repl.stop().await?;
 The REPL is run in it's own [`tempfile::TempDir`]. So any files created alongside it will be cleaned up on exit.
 */
 #![warn(missing_debug_implementations, missing_docs)]
-use futures_lite::{io::Bytes, AsyncReadExt, AsyncWriteExt, StreamExt};
+use futures_lite::{future, stream, AsyncReadExt, AsyncWriteExt, Stream};
 
-use std::{fs::File, io::Write, process::Command, string::FromUtf8Error};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    process::Command,
+    string::FromUtf8Error,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
 
-use async_process::{ChildStdout, Stdio};
+use async_process::Stdio;
 use tempfile::TempDir;
 
 const REPL_JS: &str = include_str!("./repl.js");
 const SCRIPT_FILE_NAME: &str = "script.js";
 const DEFAULT_NODE_BINARY: &str = "node";
 
-// TODO randomize EOF for each call to repl
-const DEFAULT_EOF: &[u8] = &[0, 1, 0];
+/// A frame whose length is this sentinel marks the end of a command's response instead of
+/// carrying a payload. See [`spawn_reader`].
+const END_OF_RESPONSE: u32 = u32::MAX;
+/// A frame whose length is this sentinel means the command threw; it's followed by one more
+/// frame carrying the JSON-encoded exception. See [`spawn_reader`].
+const ERROR_RESPONSE: u32 = u32::MAX - 1;
 
 type BuildCommand = dyn Fn(&Config, &str, &str) -> String;
+type Waiters = Arc<Mutex<HashMap<u32, async_channel::Sender<Result<Vec<u8>>>>>>;
+
 #[derive(derive_builder::Builder, Default)]
 #[builder(default, pattern = "owned")]
 /// Configurating for [`Repl`]. Usually you will want to setup the REPL context by importing some modules
@@ -73,14 +91,19 @@ pub struct Config {
     /// A list paths that will be copied into the [`tempfile::TempDir`] alongside the REPL script.
     /// Useful for importing custom code.
     pub copy_dirs: Vec<String>,
+    /// A list of paths that [`Repl::reload`] re-copies into the [`tempfile::TempDir`], in
+    /// addition to re-evaluating [`Config::imports`] and [`Config::before`]. Lets you iterate on
+    /// helper `.js` modules pulled in via `copy_dirs` without restarting the Node process.
+    pub watch_dirs: Vec<String>,
     /// Path to a node_modules directory which node will use.
     pub path_to_node_modules: Option<String>,
     /// Path to node binary.
     #[builder(default = "DEFAULT_NODE_BINARY.to_string()")]
     node_binary: String,
-    /// Delimiter used to signal end of a single loop in the REPL.
-    #[builder(default = "DEFAULT_EOF.to_vec()")]
-    eof: Vec<u8>,
+    /// How to spawn the process that runs the REPL script. Defaults to [`LocalBackend`], which
+    /// runs it on the host via `sh -c`. Provide a different [`ExecutionBackend`] (e.g.
+    /// [`DockerBackend`]) to run the REPL somewhere more isolated.
+    pub backend: Option<Box<dyn ExecutionBackend>>,
 }
 
 impl std::fmt::Debug for Config {
@@ -93,9 +116,10 @@ impl std::fmt::Debug for Config {
             .field("script_file_name", &self.script_file_name)
             //.field("build_command", &self.build_command)
             .field("copy_dirs", &self.copy_dirs)
+            .field("watch_dirs", &self.watch_dirs)
             .field("path_to_node_modules", &self.path_to_node_modules)
             .field("node_binary", &self.node_binary)
-            .field("eof", &self.eof)
+            .field("backend", &self.backend)
             .finish()
     }
 }
@@ -108,12 +132,22 @@ impl Config {
     /// Start Node.js and return [`Repl`].
     pub fn start(&self) -> Result<Repl> {
         let (dir, mut child) = run_code(self)?;
+        let stdout = child.stdout.take().unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let waiters: Waiters = Arc::new(Mutex::new(HashMap::new()));
+        let stderr_buf = Arc::new(Mutex::new(Vec::new()));
+        spawn_reader(stdout, waiters.clone(), stderr_buf.clone());
+        spawn_stderr_collector(stderr, stderr_buf);
         Ok(Repl {
             dir,
-            stdin: child.stdin.take().unwrap(),
-            stdout: child.stdout.take().unwrap().bytes(),
+            stdin: async_lock::Mutex::new(stdin),
             child,
-            eof: self.eof.clone(),
+            next_id: AtomicU32::new(0),
+            waiters,
+            watch_dirs: self.watch_dirs.clone(),
+            imports: self.imports.clone(),
+            before: self.before.clone(),
         })
     }
 
@@ -146,96 +180,412 @@ fn default_build_command(conf: &Config, _working_dir: &str, path_to_script: &str
     format!("{} {} {path_to_script}", node_env, conf.node_binary)
 }
 
-fn run_code(conf: &Config) -> Result<(TempDir, async_process::Child)> {
-    let working_dir = tempfile::tempdir()?;
-
-    let script_path = working_dir.path().join(&conf.script_file_name);
-    let script_file = File::create(&script_path)?;
-
-    write!(&script_file, "{}", &conf.build_script())?;
-
-    let working_dir_path = working_dir.path().display().to_string();
-    for dir in &conf.copy_dirs {
-        let dir_cp_cmd = Command::new("cp")
-            .arg("-r")
-            .arg(dir)
-            .arg(&working_dir_path)
-            .output()?;
+/// Copy each of `dirs` into `dest_dir` (via `cp -r`). Used both to seed the temp dir with
+/// [`Config::copy_dirs`] at [`Config::start`] time and to re-copy [`Config::watch_dirs`] on
+/// [`Repl::reload`].
+fn copy_dirs(dirs: &[String], dest_dir: &str) -> Result<()> {
+    for dir in dirs {
+        let dir_cp_cmd = Command::new("cp").arg("-r").arg(dir).arg(dest_dir).output()?;
         if dir_cp_cmd.status.code() != Some(0) {
             return Err(Error::CommandFailed(
                 dir_cp_cmd.status.code(),
                 format!(
-                    "failed to copy dir [{dir}] to [{working_dir_path}] got stderr: {}",
+                    "failed to copy dir [{dir}] to [{dest_dir}] got stderr: {}",
                     String::from_utf8_lossy(&dir_cp_cmd.stderr),
                 ),
             ));
         }
     }
-    let script_path_str = script_path.display().to_string();
+    Ok(())
+}
+
+fn run_code(conf: &Config) -> Result<(TempDir, async_process::Child)> {
+    let working_dir = tempfile::tempdir()?;
+
+    let script_path = working_dir.path().join(&conf.script_file_name);
+    let script_file = File::create(&script_path)?;
 
-    let cmd = match &conf.build_command {
-        Some(func) => func(conf, &working_dir_path, &script_path_str),
-        None => default_build_command(conf, &working_dir_path, &script_path_str),
+    write!(&script_file, "{}", &conf.build_script())?;
+
+    let working_dir_path = working_dir.path().display().to_string();
+    copy_dirs(&conf.copy_dirs, &working_dir_path)?;
+    // So `imports`/`before` can `require` them on this first start, same as any later
+    // `Repl::reload` finds them after re-copying.
+    copy_dirs(&conf.watch_dirs, &working_dir_path)?;
+
+    let child = match &conf.backend {
+        Some(backend) => backend.spawn(conf, working_dir.path(), &script_path)?,
+        None => LocalBackend.spawn(conf, working_dir.path(), &script_path)?,
     };
-    Ok((
-        working_dir,
-        async_process::Command::new("sh")
+    Ok((working_dir, child))
+}
+
+/// Spawns the process that runs the REPL script. The default is [`LocalBackend`], which just
+/// runs [`Config::build_command`]'s shell command on the host; swap in your own (e.g.
+/// [`DockerBackend`]) via [`Config::backend`] to run REPL code somewhere more isolated.
+pub trait ExecutionBackend: std::fmt::Debug {
+    /// Spawn the process that will run `script_path` (inside `working_dir`), wired up with piped
+    /// stdin/stdout/stderr.
+    fn spawn(
+        &self,
+        conf: &Config,
+        working_dir: &Path,
+        script_path: &Path,
+    ) -> Result<async_process::Child>;
+}
+
+/// Runs the REPL script on the host via `sh -c`. The default [`ExecutionBackend`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalBackend;
+
+impl ExecutionBackend for LocalBackend {
+    fn spawn(
+        &self,
+        conf: &Config,
+        working_dir: &Path,
+        script_path: &Path,
+    ) -> Result<async_process::Child> {
+        let working_dir_str = working_dir.display().to_string();
+        let script_path_str = script_path.display().to_string();
+        let cmd = match &conf.build_command {
+            Some(func) => func(conf, &working_dir_str, &script_path_str),
+            None => default_build_command(conf, &working_dir_str, &script_path_str),
+        };
+        Ok(async_process::Command::new("sh")
             .stdout(Stdio::piped())
             .stdin(Stdio::piped())
             .stderr(Stdio::piped())
             .arg("-c")
             .arg(cmd)
-            .spawn()?,
-    ))
+            .spawn()?)
+    }
+}
+
+/// Runs the REPL script inside a Docker container instead of on the host, so REPL code sees the
+/// container's filesystem plus the mounted `working_dir` rather than the host filesystem. Mounts
+/// `working_dir` into the container and execs `node_binary` on the script there. The container
+/// gets Docker's default networking; network access is not restricted.
+#[derive(Debug, Clone)]
+pub struct DockerBackend {
+    /// Docker image to run the script in, e.g. `"node:lts"`.
+    pub image: String,
+    /// Environment variables set inside the container, as `(name, value)` pairs.
+    pub env: Vec<(String, String)>,
+    /// Path to the `node` binary inside the container.
+    pub node_binary: String,
+}
+
+impl Default for DockerBackend {
+    fn default() -> Self {
+        DockerBackend {
+            image: "node:lts".to_string(),
+            env: Vec::new(),
+            node_binary: DEFAULT_NODE_BINARY.to_string(),
+        }
+    }
+}
+
+impl ExecutionBackend for DockerBackend {
+    fn spawn(
+        &self,
+        conf: &Config,
+        working_dir: &Path,
+        _script_path: &Path,
+    ) -> Result<async_process::Child> {
+        const CONTAINER_WORKING_DIR: &str = "/repl";
+        let mount = format!("{}:{CONTAINER_WORKING_DIR}", working_dir.display());
+        let container_script_path = format!("{CONTAINER_WORKING_DIR}/{}", conf.script_file_name);
+
+        let mut cmd = async_process::Command::new("docker");
+        cmd.arg("run")
+            .arg("--interactive")
+            .arg("--rm")
+            .arg("--volume")
+            .arg(mount)
+            .arg("--workdir")
+            .arg(CONTAINER_WORKING_DIR);
+        for (key, value) in &self.env {
+            cmd.arg("--env").arg(format!("{key}={value}"));
+        }
+        cmd.arg(&self.image)
+            .arg(&self.node_binary)
+            .arg(container_script_path);
+
+        Ok(cmd
+            .stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?)
+    }
 }
 
 /// Interface to the Node.js REPL. Send code with [`Repl::run`], stop it with [`Repl::stop`].
-#[derive(Debug)]
+///
+/// Every call to [`Repl::run`] or [`Repl::run_streaming`] is tagged with its own incrementing
+/// command id, so several calls can be pipelined onto `stdin` without waiting for a response in
+/// between; a background thread demultiplexes Node's framed `stdout` back to the right caller by
+/// id.
 pub struct Repl {
     /// Needs to be held until the working directory should be dropped.
     pub dir: TempDir,
-    /// stdin to the Node.js process.
-    pub stdin: async_process::ChildStdin,
-    /// stdout from the Node.js process.
-    pub stdout: Bytes<async_process::ChildStdout>,
+    /// stdin to the Node.js process. Held behind a lock so pipelined `run` calls can each write
+    /// their command in turn without blocking on each other's response.
+    stdin: async_lock::Mutex<async_process::ChildStdin>,
     /// Handle to the running Node.js process.
     pub child: async_process::Child,
-    /// The delimiter used to end one read-eval-print-loop
-    pub eof: Vec<u8>,
+    /// Id assigned to the next command sent to `repl.js`.
+    next_id: AtomicU32,
+    /// Callers awaiting a response, keyed by command id. Populated by [`Repl::dispatch`], drained
+    /// by the background thread spawned in [`spawn_reader`].
+    waiters: Waiters,
+    /// Copied from [`Config::watch_dirs`]; re-copied into `dir` by [`Repl::reload`].
+    watch_dirs: Vec<String>,
+    /// Copied from [`Config::imports`]; re-evaluated by [`Repl::reload`].
+    imports: Vec<String>,
+    /// Copied from [`Config::before`]; re-evaluated by [`Repl::reload`].
+    before: Vec<String>,
+}
+
+impl std::fmt::Debug for Repl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Repl")
+            .field("dir", &self.dir)
+            .field("child", &self.child)
+            .field("watch_dirs", &self.watch_dirs)
+            .finish()
+    }
 }
 
 impl Repl {
     /// Run some JavaScript. Returns whatever is through Node's `stdout`.
-    pub async fn run(&mut self, code: &str) -> Result<Vec<u8>> {
-        let code = [
-            b";(async () =>{\n",
-            code.as_bytes(),
-            b"; process.stdout.write('",
-            &self.eof,
-            b"');",
-            b"})();",
-        ]
-        .concat();
-        self.stdin.write_all(&code).await?;
-        Ok(pull_result_from_stdout(&mut self.stdout, &self.eof).await)
+    pub async fn run(&self, code: &str) -> Result<Vec<u8>> {
+        let rx = self.dispatch(code).await?;
+        let mut buff = vec![];
+        while let Ok(chunk) = rx.recv().await {
+            buff.extend(chunk?);
+        }
+        Ok(buff)
     }
 
-    /// Stop the REPL.
-    pub async fn stop(&mut self) -> Result<Vec<u8>> {
-        self.run("queue.done()'").await
+    /// Run `code` as a JavaScript expression, JSON-encoding its value on the JS side and
+    /// deserializing it into `T` on the Rust side. Saves callers from hand-writing
+    /// `process.stdout.write(JSON.stringify(x))` and `serde_json::from_slice` themselves.
+    pub async fn run_json<T: serde::de::DeserializeOwned>(&self, code: &str) -> Result<T> {
+        let bytes = self
+            .run(&format!("process.stdout.write(JSON.stringify({code}));"))
+            .await?;
+        Ok(serde_json::from_slice(&bytes)?)
     }
-}
 
-async fn pull_result_from_stdout(stdout: &mut Bytes<ChildStdout>, eof: &[u8]) -> Vec<u8> {
-    let mut buff = vec![];
-    while let Some(Ok(b)) = stdout.next().await {
-        buff.push(b);
-        if buff.ends_with(eof) {
-            buff.truncate(buff.len() - eof.len());
-            break;
+    /// Run some JavaScript, returning a [`Stream`] of the bytes Node writes to `stdout` as it
+    /// writes them, instead of buffering them until the whole call completes. The stream ends
+    /// once Node signals the response is complete.
+    pub fn run_streaming<'a>(&'a self, code: &str) -> impl Stream<Item = Result<Vec<u8>>> + 'a {
+        let code = code.to_string();
+        stream::unfold(None, move |rx: Option<async_channel::Receiver<_>>| {
+            let code = code.clone();
+            async move {
+                let rx = match rx {
+                    Some(rx) => rx,
+                    None => match self.dispatch(&code).await {
+                        Ok(rx) => rx,
+                        Err(e) => return Some((Err(e), None)),
+                    },
+                };
+                match rx.recv().await {
+                    Ok(chunk) => Some((chunk, Some(rx))),
+                    Err(_) => None,
+                }
+            }
+        })
+    }
+
+    /// Stop the REPL by closing its `stdin`, which ends `repl()`'s `for await` loop in
+    /// `repl.js` so [`Config::after`] runs and the Node process exits on its own.
+    pub async fn stop(&self) -> Result<()> {
+        self.stdin.lock().await.close().await?;
+        Ok(())
+    }
+
+    /// Re-copy [`Config::watch_dirs`] into the REPL's temp dir and re-evaluate
+    /// [`Config::imports`] and [`Config::before`] in the live context, returning any output. Lets
+    /// you iterate on helper `.js` modules pulled in via `copy_dirs`/`watch_dirs` without
+    /// restarting the Node process.
+    ///
+    /// Neither plain nor indirect `eval` keep a `const`/`let`/`class` binding alive past the call
+    /// that declared it (indirect `eval` only leaks `var`s and function declarations, and only
+    /// onto `globalThis` itself), so re-running [`Config::imports`] verbatim would just discard
+    /// whatever it bound -- later [`Repl::run`] calls would still see the binding from
+    /// [`Config::build_script`]'s original, never-refreshed top-level evaluation. Write
+    /// [`Config::imports`] as `globalThis.x = require(...)` if later code needs to reach them, the
+    /// same way anything [`Config::before`] wants to leave behind already has to.
+    ///
+    /// `require` also caches modules by resolved path, so a bare re-`require` of an edited file
+    /// under [`Config::watch_dirs`] would keep returning the stale module from [`Config::start`].
+    /// `reload` evicts every cached module resolved from inside [`Config::watch_dirs`] before
+    /// re-evaluating [`Config::imports`], so the next `require` of one reads it fresh off disk.
+    pub async fn reload(&self) -> Result<Vec<u8>> {
+        let dir = self.dir.path().display().to_string();
+        copy_dirs(&self.watch_dirs, &dir)?;
+
+        let watched_dir_names: Vec<&str> = self
+            .watch_dirs
+            .iter()
+            .filter_map(|d| Path::new(d).file_name())
+            .filter_map(|n| n.to_str())
+            .collect();
+        let mut code = format!(
+            "evictWatchedModulesFromRequireCache({});\n",
+            serde_json::to_string(&watched_dir_names)?
+        );
+        code.push_str(&self.imports.join(";\n"));
+        code.push_str(";\n");
+        code.push_str(&self.before.join(";\n"));
+        self.run(&code).await
+    }
+
+    /// Send `code` to the REPL under a fresh command id, registering a channel that the
+    /// background reader feeds with that command's response chunks.
+    async fn dispatch(&self, code: &str) -> Result<async_channel::Receiver<Result<Vec<u8>>>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = async_channel::unbounded();
+        self.waiters.lock().unwrap().insert(id, tx);
+        if let Err(e) = self
+            .stdin
+            .lock()
+            .await
+            .write_all(&wrap_call(id, code))
+            .await
+        {
+            self.waiters.lock().unwrap().remove(&id);
+            return Err(e.into());
         }
+        Ok(rx)
     }
-    buff
+}
+
+/// Wraps `code` so `repl.js` can recover the command id it belongs to, and so every write it
+/// makes to `stdout`, the frame marking its completion, or the frame reporting an uncaught
+/// exception, is tagged with that id. The result is itself length-prefixed (u32 LE) so `repl.js`
+/// can reassemble a full command even if it lands in the same `stdin` read as another pipelined
+/// command, or is split across several reads.
+fn wrap_call(id: u32, code: &str) -> Vec<u8> {
+    let prelude = format!("{id}\0;(async () => {{\n");
+    let epilogue =
+        format!("\n}})().then(() => writeEndFrame({id}), (e) => writeErrorFrame({id}, e));");
+    let body = [prelude.as_bytes(), code.as_bytes(), epilogue.as_bytes()].concat();
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&body);
+    frame
+}
+
+/// The `{name, message, stack}` shape `repl.js` serializes an uncaught JS exception to.
+#[derive(serde::Deserialize)]
+struct JsExceptionPayload {
+    name: String,
+    message: String,
+    stack: String,
+}
+
+/// Drains `stdout` on a dedicated thread, decoding the `id`+`len`+payload frames `repl.js`
+/// writes and routing each one to the waiter registered for that id in `waiters`. This crate has
+/// no async executor of its own to spawn a task onto, so a plain OS thread driving
+/// [`future::block_on`] plays that role instead.
+fn spawn_reader(
+    mut stdout: async_process::ChildStdout,
+    waiters: Waiters,
+    stderr_buf: Arc<Mutex<Vec<u8>>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        future::block_on(async move {
+            let mut header = [0u8; 8];
+            while stdout.read_exact(&mut header).await.is_ok() {
+                let id = u32::from_le_bytes(header[0..4].try_into().unwrap());
+                let len = u32::from_le_bytes(header[4..8].try_into().unwrap());
+                match len {
+                    END_OF_RESPONSE => {
+                        waiters.lock().unwrap().remove(&id);
+                    }
+                    ERROR_RESPONSE => {
+                        if stdout.read_exact(&mut header).await.is_err() {
+                            break;
+                        }
+                        let payload_len =
+                            u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+                        let mut payload = vec![0u8; payload_len];
+                        if stdout.read_exact(&mut payload).await.is_err() {
+                            break;
+                        }
+                        let sender = waiters.lock().unwrap().remove(&id);
+                        if let Some(sender) = sender {
+                            let _ = sender.send(Err(js_exception(&payload, &stderr_buf))).await;
+                        }
+                    }
+                    len => {
+                        let mut payload = vec![0u8; len as usize];
+                        if stdout.read_exact(&mut payload).await.is_err() {
+                            break;
+                        }
+                        let sender = waiters.lock().unwrap().get(&id).cloned();
+                        if let Some(sender) = sender {
+                            let _ = sender.send(Ok(payload)).await;
+                        }
+                    }
+                }
+            }
+            // `stdout` closed (Node exited or crashed) or a frame was cut off mid-read; either
+            // way no more frames are coming. Fail every waiter still registered instead of
+            // leaving its caller parked on a channel nothing will ever send to, and attach
+            // whatever Node had written to its own stderr so a crash isn't a contentless error.
+            let stderr = String::from_utf8_lossy(&stderr_buf.lock().unwrap()).into_owned();
+            for (_, sender) in waiters.lock().unwrap().drain() {
+                let _ = sender.try_send(Err(Error::ReplExited {
+                    stderr: stderr.clone(),
+                }));
+            }
+        });
+    })
+}
+
+/// Builds an [`Error::JsException`] from the JSON error frame `repl.js` sent, attaching whatever
+/// Node has written to its own stderr so far (e.g. `console.error` output or warnings). A syntax
+/// error in the command's code arrives as the error frame itself; stderr from a crashed process
+/// is reported through [`Error::ReplExited`] instead.
+fn js_exception(payload: &[u8], stderr_buf: &Mutex<Vec<u8>>) -> Error {
+    let stderr = String::from_utf8_lossy(&stderr_buf.lock().unwrap()).into_owned();
+    match serde_json::from_slice::<JsExceptionPayload>(payload) {
+        Ok(JsExceptionPayload {
+            name,
+            message,
+            stack,
+        }) => Error::JsException {
+            name,
+            message,
+            stack,
+            stderr,
+        },
+        Err(e) => Error::SerdeJsonError(e),
+    }
+}
+
+/// Drains Node's `stderr` on a dedicated thread into `buf`, so it's available to attach to a
+/// [`Error::JsException`] without blocking on a read when the exception occurs.
+fn spawn_stderr_collector(
+    mut stderr: async_process::ChildStderr,
+    buf: Arc<Mutex<Vec<u8>>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        future::block_on(async move {
+            let mut chunk = [0u8; 4096];
+            loop {
+                match stderr.read(&mut chunk).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => buf.lock().unwrap().extend_from_slice(&chunk[..n]),
+                }
+            }
+        });
+    })
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -251,15 +601,33 @@ pub enum Error {
     SerdeJsonError(#[from] serde_json::Error),
     #[error("Error building config: {0}")]
     ConfigBuilderError(#[from] ConfigBuilderError),
+    #[error("JS exception thrown: {name}: {message}\nstack:\n{stack}\nstderr:\n{stderr}")]
+    JsException {
+        /// The JS `Error`'s `name`, e.g. `TypeError`.
+        name: String,
+        /// The JS `Error`'s `message`.
+        message: String,
+        /// The JS `Error`'s `stack` trace.
+        stack: String,
+        /// Whatever Node had written to its own stderr by the time the exception was reported.
+        stderr: String,
+    },
+    #[error("the REPL's Node process exited without responding\nstderr:\n{stderr}")]
+    ReplExited {
+        /// Whatever Node had written to its own stderr by the time the reader noticed it exited.
+        stderr: String,
+    },
 }
 type Result<T> = core::result::Result<T, Error>;
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use futures_lite::StreamExt;
+
     #[tokio::test]
     async fn read_eval_print_macro_works() -> Result<()> {
-        let mut context: Repl = Config::build()?.start()?;
+        let context: Repl = Config::build()?.start()?;
         let result = context.run("console.log('Hello, world!');").await?;
         assert_eq!(result, b"Hello, world!\n");
         let result = context
@@ -277,7 +645,153 @@ process.stdout.write(`${b}`);
         assert_eq!(result, b"77");
 
         let _result = context.stop().await?;
-        let _ = context.child.output().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_streaming_emits_chunks_and_stops_at_completion() -> Result<()> {
+        let context: Repl = Config::build()?.start()?;
+        let mut collected = vec![];
+        {
+            let mut stream =
+                context.run_streaming("process.stdout.write('a'); process.stdout.write('b');");
+            while let Some(chunk) = stream.next().await {
+                collected.extend(chunk?);
+            }
+        }
+        assert_eq!(collected, b"ab");
+
+        let _result = context.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipelined_run_calls_do_not_leak_into_each_other() -> Result<()> {
+        let context: Repl = Config::build()?.start()?;
+        let (a, b) = futures_lite::future::zip(
+            context.run("await new Promise(r => setTimeout(r, 20)); process.stdout.write('a');"),
+            context.run("process.stdout.write('b');"),
+        )
+        .await;
+        assert_eq!(a?, b"a");
+        assert_eq!(b?, b"b");
+
+        let _result = context.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_surfaces_thrown_js_exceptions() -> Result<()> {
+        let context: Repl = Config::build()?.start()?;
+        let err = context
+            .run("throw new TypeError('boom');")
+            .await
+            .unwrap_err();
+        match err {
+            Error::JsException { name, message, .. } => {
+                assert_eq!(name, "TypeError");
+                assert_eq!(message, "boom");
+            }
+            other => panic!("expected Error::JsException, got {other:?}"),
+        }
+
+        let result = context.run("process.stdout.write('still alive');").await?;
+        assert_eq!(result, b"still alive");
+
+        let _result = context.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_json_deserializes_the_expressions_value() -> Result<()> {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Point {
+            x: i64,
+            y: i64,
+        }
+
+        let context: Repl = Config::build()?.start()?;
+        let point: Point = context.run_json("({ x: 1, y: 2 })").await?;
+        assert_eq!(point, Point { x: 1, y: 2 });
+
+        let nums: Vec<i64> = context.run_json("[1, 2, 3]").await?;
+        assert_eq!(nums, vec![1, 2, 3]);
+
+        let _result = context.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reload_recopies_watch_dirs_and_reevaluates_setup() -> Result<()> {
+        let watch_dir = tempfile::tempdir()?;
+        std::fs::write(watch_dir.path().join("greeting.txt"), "hello")?;
+        let watch_dir_path = watch_dir.path().display().to_string();
+        let watch_dir_name = watch_dir
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+
+        let context: Repl = ConfigBuilder::default()
+            .watch_dirs(vec![watch_dir_path])
+            .before(vec![format!(
+                "globalThis.greeting = require('fs').readFileSync(require('path').join(__dirname, '{watch_dir_name}', 'greeting.txt'), 'utf8');"
+            )])
+            .build()?
+            .start()?;
+
+        let result = context
+            .run("process.stdout.write(globalThis.greeting);")
+            .await?;
+        assert_eq!(result, b"hello");
+
+        std::fs::write(watch_dir.path().join("greeting.txt"), "goodbye")?;
+        context.reload().await?;
+
+        let result = context
+            .run("process.stdout.write(globalThis.greeting);")
+            .await?;
+        assert_eq!(result, b"goodbye");
+
+        let _result = context.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reload_evicts_watched_modules_so_require_reads_them_fresh() -> Result<()> {
+        let watch_dir = tempfile::tempdir()?;
+        std::fs::write(watch_dir.path().join("helper.js"), "module.exports = 'v1';")?;
+        let watch_dir_path = watch_dir.path().display().to_string();
+        let watch_dir_name = watch_dir
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+
+        let context: Repl = ConfigBuilder::default()
+            .watch_dirs(vec![watch_dir_path])
+            .imports(vec![format!(
+                "globalThis.helper = require('./{watch_dir_name}/helper.js');"
+            )])
+            .build()?
+            .start()?;
+
+        let result = context
+            .run("process.stdout.write(globalThis.helper);")
+            .await?;
+        assert_eq!(result, b"v1");
+
+        std::fs::write(watch_dir.path().join("helper.js"), "module.exports = 'v2';")?;
+        context.reload().await?;
+
+        let result = context
+            .run("process.stdout.write(globalThis.helper);")
+            .await?;
+        assert_eq!(result, b"v2");
+
+        let _result = context.stop().await?;
         Ok(())
     }
 }